@@ -1,6 +1,7 @@
 use chrono;
 use colored::*;
-use serde_derive::Deserialize;
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
 use shellexpand;
 use sqlite::State;
 use std::convert::TryFrom;
@@ -20,7 +21,7 @@ use toml::de;
 const CONFIG_FILENAME: &'static str = ".recall.toml";
 const DEFAULT_EDITOR: &'static str = "vi";
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 struct Config {
     db_path: String,
     editor_command: Option<Vec<String>>,
@@ -50,20 +51,60 @@ struct Options {
 
     #[structopt(short = "t", long = "text")]
     text: Option<String>,
+
+    #[structopt(short = "s", long = "search")]
+    search: Option<String>,
+
+    #[structopt(long = "parent")]
+    parent: Option<i64>,
+
+    #[structopt(long = "move", number_of_values = 2)]
+    move_indices: Option<Vec<i64>>,
+
+    #[structopt(long = "backlinks")]
+    backlinks: Option<i64>,
+
+    #[structopt(long = "export")]
+    export: Option<String>,
+
+    #[structopt(long = "restore")]
+    restore: Option<i64>,
+
+    #[structopt(long = "deleted")]
+    deleted: bool,
+
+    #[structopt(long = "configure")]
+    configure: bool,
+
+    #[structopt(long = "db-path")]
+    db_path: Option<String>,
+
+    #[structopt(long = "editor-command", min_values = 1)]
+    editor_command: Option<Vec<String>>,
+
+    #[structopt(short = "c", long = "category")]
+    category: Option<String>,
+
+    #[structopt(long = "categories")]
+    categories: bool,
+
+    #[structopt(long = "list")]
+    list: bool,
 }
 
 fn find_config_file() -> Option<Box<PathBuf>> {
-    let original_cwd = std::env::current_dir().unwrap();
+    let mut dir = std::env::current_dir().unwrap();
 
-    // TODO: recursve up directories
-    let path = Path::new(CONFIG_FILENAME);
-    if path.exists() {
-        let result = Box::new(path.to_path_buf());
-        std::env::set_current_dir(original_cwd).unwrap();
-        return Some(result);
-    }
+    loop {
+        let path = dir.join(CONFIG_FILENAME);
+        if path.exists() {
+            return Some(Box::new(path));
+        }
 
-    return None;
+        if !dir.pop() {
+            return None;
+        }
+    }
 }
 
 enum NoteContent {
@@ -73,67 +114,173 @@ enum NoteContent {
 }
 
 struct Note {
+    id: i64,
     datetime_millis: i64,
     title: String,
     content: Option<NoteContent>,
+    parent_id: Option<i64>,
+    position: i64,
+    updated_datetime_millis: Option<i64>,
+    lastview_datetime_millis: Option<i64>,
+    deleted_datetime_millis: Option<i64>,
+    category: Option<String>,
+}
+
+fn now_millis() -> i64 {
+    i64::try_from(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis(),
+    )
+    .unwrap()
 }
 
 impl Note {
     fn new(title: String) -> Note {
+        let datetime_millis = now_millis();
         Note {
-            datetime_millis: i64::try_from(
-                std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis(),
-            )
-            .unwrap(),
+            id: 0,
+            datetime_millis: datetime_millis,
             title: title,
             content: None,
+            parent_id: None,
+            position: 0,
+            updated_datetime_millis: Some(datetime_millis),
+            lastview_datetime_millis: None,
+            deleted_datetime_millis: None,
+            category: None,
         }
     }
 
     fn new_with_path(title: String, path: String) -> Note {
+        let datetime_millis = now_millis();
         Note {
-            datetime_millis: i64::try_from(
-                std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis(),
-            )
-            .unwrap(),
+            id: 0,
+            datetime_millis: datetime_millis,
             title: title,
             content: Some(NoteContent::Path(path)),
+            parent_id: None,
+            position: 0,
+            updated_datetime_millis: Some(datetime_millis),
+            lastview_datetime_millis: None,
+            deleted_datetime_millis: None,
+            category: None,
         }
     }
 
     fn new_with_link(title: String, link: String) -> Note {
+        let datetime_millis = now_millis();
         Note {
-            datetime_millis: i64::try_from(
-                std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis(),
-            )
-            .unwrap(),
+            id: 0,
+            datetime_millis: datetime_millis,
             title: title,
             content: Some(NoteContent::Link(link)),
+            parent_id: None,
+            position: 0,
+            updated_datetime_millis: Some(datetime_millis),
+            lastview_datetime_millis: None,
+            deleted_datetime_millis: None,
+            category: None,
         }
     }
 
     fn new_with_text(title: String, text: String) -> Note {
+        let datetime_millis = now_millis();
         Note {
-            datetime_millis: i64::try_from(
-                std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis(),
-            )
-            .unwrap(),
+            id: 0,
+            datetime_millis: datetime_millis,
             title: title,
             content: Some(NoteContent::Text(text)),
+            parent_id: None,
+            position: 0,
+            updated_datetime_millis: Some(datetime_millis),
+            lastview_datetime_millis: None,
+            deleted_datetime_millis: None,
+            category: None,
         }
     }
+
+    fn with_parent(mut self, parent_id: Option<i64>) -> Note {
+        self.parent_id = parent_id;
+        self
+    }
+
+    fn with_category(mut self, category: Option<String>) -> Note {
+        self.category = category;
+        self
+    }
+}
+
+fn find_references(text: &str) -> Vec<String> {
+    let re = Regex::new(r"\[\[\s*([^\[\]]+?)\s*\]\]").unwrap();
+    re.captures_iter(text)
+        .map(|capture| capture[1].to_string())
+        .collect()
+}
+
+fn refresh_references(connection: &sqlite::Connection, source_id: i64, text: &str) -> sqlite::Result<()> {
+    let mut delete_statement =
+        connection.prepare("DELETE FROM note_references WHERE source_id = ?")?;
+    delete_statement.bind(1, source_id)?;
+    delete_statement.next()?;
+
+    for target_title in find_references(text) {
+        let mut insert_statement = connection.prepare(
+            "INSERT INTO note_references (source_id, target_title) VALUES (?, ?)",
+        )?;
+        insert_statement.bind(1, source_id)?;
+        insert_statement.bind(2, target_title.as_str())?;
+        insert_statement.next()?;
+    }
+
+    Ok(())
+}
+
+fn reference_exists(connection: &sqlite::Connection, title: &str) -> sqlite::Result<bool> {
+    let mut statement =
+        connection.prepare("SELECT 1 FROM notes WHERE title = ? AND deleted_datetime IS NULL")?;
+    statement.bind(1, title)?;
+    Ok(statement.next()? == State::Row)
+}
+
+fn find_backlinks(connection: sqlite::Connection, title: &str) -> sqlite::Result<Vec<(String, i64)>> {
+    let mut statement = connection.prepare(
+        "
+        SELECT notes.title, notes.datetime
+        FROM notes JOIN note_references ON notes.id = note_references.source_id
+        WHERE note_references.target_title = ? AND notes.deleted_datetime IS NULL
+        ",
+    )?;
+    statement.bind(1, title)?;
+
+    let mut result = Vec::<(String, i64)>::new();
+    while let State::Row = statement.next()? {
+        let title = statement.read::<String>(0)?;
+        let datetime = statement.read::<i64>(1)?;
+        result.push((title, datetime));
+    }
+
+    Ok(result)
+}
+
+fn update_note_text(connection: &sqlite::Connection, id: i64, text: &str) -> sqlite::Result<()> {
+    let mut statement =
+        connection.prepare("UPDATE notes SET text = ?, updated_datetime = ? WHERE id = ?")?;
+    statement.bind(1, text)?;
+    statement.bind(2, now_millis())?;
+    statement.bind(3, id)?;
+    statement.next()?;
+    refresh_references(connection, id, text)
+}
+
+fn next_position(connection: &sqlite::Connection, parent_id: Option<i64>) -> sqlite::Result<i64> {
+    let mut statement = connection.prepare(
+        "SELECT COALESCE(MAX(position), -1) + 1 FROM notes WHERE parent_id IS ? AND deleted_datetime IS NULL",
+    )?;
+    statement.bind(1, parent_id)?;
+    statement.next()?;
+    statement.read::<i64>(0)
 }
 
 fn insert_note(connnection: sqlite::Connection, note: Note) -> sqlite::Result<()> {
@@ -146,10 +293,12 @@ fn insert_note(connnection: sqlite::Connection, note: Note) -> sqlite::Result<()
         },
     };
 
+    let position = next_position(&connnection, note.parent_id)?;
+
     let mut statement = connnection.prepare(
         "
-    INSERT INTO notes (datetime, title, path, link, text)
-    VALUES (?, ?, ?, ?, ?)
+    INSERT INTO notes (datetime, title, path, link, text, parent_id, position, updated_datetime, category)
+    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
     ",
     )?;
 
@@ -158,50 +307,139 @@ fn insert_note(connnection: sqlite::Connection, note: Note) -> sqlite::Result<()
     statement.bind(3, path.as_ref().map(|a| a.as_str()))?;
     statement.bind(4, link.as_ref().map(|a| a.as_str()))?;
     statement.bind(5, text.as_ref().map(|a| a.as_str()))?;
+    statement.bind(6, note.parent_id)?;
+    statement.bind(7, position)?;
+    statement.bind(8, note.updated_datetime_millis.unwrap_or(note.datetime_millis))?;
+    statement.bind(9, note.category.as_deref())?;
     statement.next()?;
+
+    if let Some(text) = &text {
+        let mut rowid_statement = connnection.prepare("select last_insert_rowid()")?;
+        rowid_statement.next()?;
+        let note_id = rowid_statement.read::<i64>(0)?;
+        refresh_references(&connnection, note_id, text)?;
+    }
+
     Ok(())
 }
 
 fn read_note(statement: &mut sqlite::Statement) -> sqlite::Result<Note> {
-    let datetime = statement.read::<i64>(0)?;
-    let title = statement.read::<String>(1)?;
-    let path = statement.read::<Option<String>>(2)?;
-    let link = statement.read::<Option<String>>(3)?;
-    let text = statement.read::<Option<String>>(4)?;
+    let id = statement.read::<i64>(0)?;
+    let datetime = statement.read::<i64>(1)?;
+    let title = statement.read::<String>(2)?;
+    let path = statement.read::<Option<String>>(3)?;
+    let link = statement.read::<Option<String>>(4)?;
+    let text = statement.read::<Option<String>>(5)?;
+    let parent_id = statement.read::<Option<i64>>(6)?;
+    let position = statement.read::<i64>(7)?;
+    let updated_datetime = statement.read::<Option<i64>>(8)?;
+    let lastview_datetime = statement.read::<Option<i64>>(9)?;
+    let deleted_datetime = statement.read::<Option<i64>>(10)?;
+    let category = statement.read::<Option<String>>(11)?;
 
-    let note = if let Some(path) = path {
-        Note {
-            datetime_millis: datetime,
-            title: title,
-            content: Some(NoteContent::Path(path)),
-        }
+    let content = if let Some(path) = path {
+        Some(NoteContent::Path(path))
     } else if let Some(link) = link {
-        Note {
-            datetime_millis: datetime,
-            title: title,
-            content: Some(NoteContent::Link(link)),
-        }
+        Some(NoteContent::Link(link))
     } else if let Some(text) = text {
-        Note {
-            datetime_millis: datetime,
-            title: title,
-            content: Some(NoteContent::Text(text)),
-        }
+        Some(NoteContent::Text(text))
     } else {
-        Note {
-            datetime_millis: datetime,
-            title: title,
-            content: None,
-        }
+        None
     };
-    Ok(note)
+
+    Ok(Note {
+        id: id,
+        datetime_millis: datetime,
+        title: title,
+        content: content,
+        parent_id: parent_id,
+        position: position,
+        updated_datetime_millis: updated_datetime,
+        lastview_datetime_millis: lastview_datetime,
+        deleted_datetime_millis: deleted_datetime,
+        category: category,
+    })
 }
 
-fn list_notes(connection: sqlite::Connection) -> sqlite::Result<Vec<Note>> {
+const NOTE_COLUMNS: &'static str =
+    "notes.id, notes.datetime, notes.title, notes.path, notes.link, notes.text, notes.parent_id, notes.position, notes.updated_datetime, notes.lastview_datetime, notes.deleted_datetime, notes.category";
+
+const TREE_CTE: &'static str = "
+    WITH RECURSIVE tree(id, depth, sort_path) AS (
+        SELECT id, 0, printf('%08d', position)
+        FROM notes WHERE parent_id IS NULL AND deleted_datetime IS NULL
+        UNION ALL
+        SELECT n.id, tree.depth + 1, tree.sort_path || '.' || printf('%08d', n.position)
+        FROM notes n JOIN tree ON n.parent_id = tree.id
+        WHERE n.deleted_datetime IS NULL
+    )
+";
+
+fn list_notes(connection: sqlite::Connection, category_filter: Option<&str>) -> sqlite::Result<Vec<(Note, i64)>> {
+    // The tree is walked unfiltered so that a note hidden by the category
+    // filter doesn't leave its visible descendants indented as if it were
+    // still there; `passthrough` tracks how many *visible* ancestors precede
+    // each row, indexed by the row's raw tree depth.
+    let mut statement = connection.prepare(format!(
+        "{}
+        SELECT {}, tree.depth
+        FROM notes JOIN tree ON notes.id = tree.id
+        ORDER BY tree.sort_path",
+        TREE_CTE, NOTE_COLUMNS
+    ))?;
+
+    let mut result = Vec::<(Note, i64)>::new();
+    let mut passthrough = Vec::<i64>::new();
+    while let State::Row = statement.next()? {
+        let note = read_note(&mut statement)?;
+        let raw_depth = statement.read::<i64>(12)?;
+
+        let raw_depth_usize = usize::try_from(raw_depth).unwrap_or(0);
+        passthrough.truncate(raw_depth_usize);
+        let ancestor_visible_count = *passthrough.last().unwrap_or(&0);
+
+        let visible = category_filter.is_none() || note.category.as_deref() == category_filter;
+        passthrough.push(if visible {
+            ancestor_visible_count + 1
+        } else {
+            ancestor_visible_count
+        });
+
+        if visible {
+            result.push((note, ancestor_visible_count));
+        }
+    }
+
+    Ok(result)
+}
+
+fn list_categories(connection: sqlite::Connection) -> sqlite::Result<Vec<(String, i64)>> {
     let mut statement = connection.prepare(
-        "SELECT datetime, title, path, link, text FROM notes WHERE archived = FALSE ORDER BY datetime",
+        "
+        SELECT category, COUNT(*)
+        FROM notes
+        WHERE category IS NOT NULL AND deleted_datetime IS NULL
+        GROUP BY category
+        ORDER BY category
+        ",
     )?;
 
+    let mut result = Vec::<(String, i64)>::new();
+    while let State::Row = statement.next()? {
+        let category = statement.read::<String>(0)?;
+        let count = statement.read::<i64>(1)?;
+        result.push((category, count));
+    }
+
+    Ok(result)
+}
+
+fn list_deleted_notes(connection: sqlite::Connection) -> sqlite::Result<Vec<Note>> {
+    let mut statement = connection.prepare(format!(
+        "SELECT {} FROM notes WHERE deleted_datetime IS NOT NULL ORDER BY deleted_datetime DESC",
+        NOTE_COLUMNS
+    ))?;
+
     let mut result = Vec::<Note>::new();
     while let State::Row = statement.next()? {
         result.push(read_note(&mut statement)?);
@@ -210,23 +448,173 @@ fn list_notes(connection: sqlite::Connection) -> sqlite::Result<Vec<Note>> {
     Ok(result)
 }
 
+// Wraps a user search query as a single FTS5 string literal so that plain
+// text like `AND`/`OR`/`NOT`/`NEAR` or a stray `"`/`*` is matched as a
+// literal phrase instead of being parsed as FTS5 query syntax.
+fn fts5_quote(query: &str) -> String {
+    format!("\"{}\"", query.replace('"', "\"\""))
+}
+
+fn search_notes(connection: sqlite::Connection, query: &str) -> sqlite::Result<Vec<(Note, String)>> {
+    let mut statement = connection.prepare(format!(
+        "
+        SELECT {}, snippet(notes_fts, -1, '[', ']', '...', 10)
+        FROM notes
+        JOIN notes_fts ON notes.id = notes_fts.rowid
+        WHERE notes_fts MATCH ? AND notes.deleted_datetime IS NULL
+        ORDER BY rank
+        ",
+        NOTE_COLUMNS
+    ))?;
+
+    statement.bind(1, fts5_quote(query).as_str())?;
+
+    let mut result = Vec::<(Note, String)>::new();
+    while let State::Row = statement.next()? {
+        let note = read_note(&mut statement)?;
+        let snippet = statement.read::<String>(12)?;
+        result.push((note, snippet));
+    }
+
+    Ok(result)
+}
+
 fn note_display_string(note: &Note) -> String {
+    note_display_string_at_depth(note, 0, true)
+}
+
+fn note_display_string_at_depth(note: &Note, depth: i64, show_category: bool) -> String {
     let content_display = match &note.content {
         Some(NoteContent::Path(path)) => Some("path".italic()),
         Some(NoteContent::Link(link)) => Some("link".italic()),
         Some(NoteContent::Text(text)) => Some("text".italic()),
         None => None,
     };
-    let title_display = note.title.yellow();
+    let indent = "  ".repeat(usize::try_from(depth).unwrap_or(0));
+    let category_tag = match &note.category {
+        Some(category) if show_category => format!(" [{}]", category).cyan().to_string(),
+        _ => String::new(),
+    };
+    let title_display = format!("{}{}{}", indent, note.title.yellow(), category_tag);
     let time_display = chrono::DateTime::<chrono::Local>::from(
         std::time::UNIX_EPOCH
             + std::time::Duration::from_millis(u64::try_from(note.datetime_millis).unwrap()),
     )
     .format("%F %H:%M:%S");
+
+    let format_time = |millis: i64| {
+        chrono::DateTime::<chrono::Local>::from(
+            std::time::UNIX_EPOCH + std::time::Duration::from_millis(u64::try_from(millis).unwrap()),
+        )
+        .format("%F %H:%M:%S")
+        .to_string()
+    };
+
+    let mut lifecycle_display = String::new();
+    if let Some(updated_millis) = note.updated_datetime_millis {
+        if updated_millis != note.datetime_millis {
+            lifecycle_display.push_str(&format!(" (edited {})", format_time(updated_millis)));
+        }
+    }
+    if let Some(lastview_millis) = note.lastview_datetime_millis {
+        lifecycle_display.push_str(&format!(" (viewed {})", format_time(lastview_millis)));
+    }
+
     match content_display {
-        None => format!("{}\t\t{}", time_display, title_display),
+        None => format!("{}\t\t{}{}", time_display, title_display, lifecycle_display),
         Some(content_display) => {
-            format!("{}\t{}\t{}", time_display, content_display, title_display)
+            format!(
+                "{}\t{}\t{}{}",
+                time_display, content_display, title_display, lifecycle_display
+            )
+        }
+    }
+}
+
+enum Target {
+    Html,
+    Markdown,
+}
+
+impl std::str::FromStr for Target {
+    type Err = RecallError;
+
+    fn from_str(s: &str) -> Result<Target, RecallError> {
+        match s {
+            "html" => Ok(Target::Html),
+            "md" => Ok(Target::Markdown),
+            _ => Err(RecallError {
+                message: format!("Unknown export target '{}'. Expected 'html' or 'md'", s),
+            }),
+        }
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn compile(notes: &[Note], target: Target) -> String {
+    match target {
+        Target::Html => {
+            let mut document = String::from("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>recall</title></head>\n<body>\n");
+            for note in notes {
+                let time_display = chrono::DateTime::<chrono::Local>::from(
+                    std::time::UNIX_EPOCH
+                        + std::time::Duration::from_millis(
+                            u64::try_from(note.datetime_millis).unwrap(),
+                        ),
+                )
+                .format("%F %H:%M:%S");
+                document.push_str(&format!("<section>\n<h2>{}</h2>\n", escape_html(&note.title)));
+                document.push_str(&format!("<time>{}</time>\n", time_display));
+                match &note.content {
+                    Some(NoteContent::Link(link)) => {
+                        document.push_str(&format!("<a href=\"{}\">{}</a>\n", escape_html(link), escape_html(link)));
+                    }
+                    Some(NoteContent::Path(path)) => {
+                        document.push_str(&format!("<a href=\"file://{}\">{}</a>\n", escape_html(path), escape_html(path)));
+                    }
+                    Some(NoteContent::Text(text)) => {
+                        document.push_str(&format!("<p>{}</p>\n", escape_html(text)));
+                    }
+                    None => (),
+                }
+                document.push_str("</section>\n");
+            }
+            document.push_str("</body>\n</html>\n");
+            document
+        }
+        Target::Markdown => {
+            let mut document = String::new();
+            for note in notes {
+                let time_display = chrono::DateTime::<chrono::Local>::from(
+                    std::time::UNIX_EPOCH
+                        + std::time::Duration::from_millis(
+                            u64::try_from(note.datetime_millis).unwrap(),
+                        ),
+                )
+                .format("%F %H:%M:%S");
+                document.push_str(&format!("## {}\n\n", note.title));
+                document.push_str(&format!("*{}*\n\n", time_display));
+                match &note.content {
+                    Some(NoteContent::Link(link)) => {
+                        document.push_str(&format!("[{}]({})\n\n", link, link));
+                    }
+                    Some(NoteContent::Path(path)) => {
+                        document.push_str(&format!("[{}]({})\n\n", path, path));
+                    }
+                    Some(NoteContent::Text(text)) => {
+                        document.push_str(&format!("{}\n\n", text));
+                    }
+                    None => (),
+                }
+            }
+            document
         }
     }
 }
@@ -242,10 +630,30 @@ fn note_content_display_string(note: &Note) -> String {
     }
 }
 
-fn read_nth_note(connection: sqlite::Connection, note_index: i64) -> sqlite::Result<Note> {
-    let mut statement = connection.prepare(
-        "SELECT id, title, path, link, text FROM notes WHERE archived = FALSE ORDER BY datetime",
-    )?;
+fn bump_lastview(connection: &sqlite::Connection, id: i64) -> sqlite::Result<()> {
+    let mut statement =
+        connection.prepare("UPDATE notes SET lastview_datetime = ? WHERE id = ?")?;
+    statement.bind(1, now_millis())?;
+    statement.bind(2, id)?;
+    statement.next()?;
+    Ok(())
+}
+
+fn read_nth_note(
+    connection: &sqlite::Connection,
+    note_index: i64,
+    category_filter: Option<&str>,
+) -> Result<Note, RecallError> {
+    let mut statement = connection.prepare(format!(
+        "{}
+        SELECT {}
+        FROM notes JOIN tree ON notes.id = tree.id
+        WHERE (? IS NULL OR notes.category = ?)
+        ORDER BY tree.sort_path",
+        TREE_CTE, NOTE_COLUMNS
+    ))?;
+    statement.bind(1, category_filter)?;
+    statement.bind(2, category_filter)?;
 
     let mut current_index = 0;
     while let State::Row = statement.next()? {
@@ -254,11 +662,13 @@ fn read_nth_note(connection: sqlite::Connection, note_index: i64) -> sqlite::Res
             continue;
         }
 
-        return read_note(&mut statement);
+        let note = read_note(&mut statement)?;
+        return Ok(note);
     }
 
-    // TODO: should be error
-    panic!("SHould be error");
+    Err(RecallError {
+        message: format!("No note found at index {}", note_index),
+    })
 }
 
 #[derive(Debug)]
@@ -379,6 +789,45 @@ fn edit_text_in_editor(config: &Config, text: String) -> Result<String, EditorEr
     }
 }
 
+fn configure(config_path: &Path, mut config: Config, options: &Options) -> Result<(), RecallError> {
+    if let Some(editor_command) = &options.editor_command {
+        if editor_command.is_empty() {
+            return Err(RecallError {
+                message: String::from(
+                    "The first entry in editor_command must be the path to a text editor program",
+                ),
+            });
+        }
+    }
+
+    if options.db_path.is_some() || options.editor_command.is_some() {
+        if let Some(db_path) = &options.db_path {
+            config.db_path = db_path.clone();
+        }
+        if let Some(editor_command) = &options.editor_command {
+            config.editor_command = Some(editor_command.clone());
+        }
+    } else {
+        let config_string = toml::to_string(&config).map_err(|e| RecallError {
+            message: format!("Error serializing config: {}", e),
+        })?;
+        let new_config_string = edit_text_in_editor(&config, config_string)?;
+        config = toml::from_str::<Config>(&new_config_string).map_err(|e| RecallError {
+            message: format!("Could not parse edited config: {}", e),
+        })?;
+    }
+
+    let config_string = toml::to_string(&config).map_err(|e| RecallError {
+        message: format!("Error serializing config: {}", e),
+    })?;
+    fs::write(config_path, config_string).map_err(|e| RecallError {
+        message: format!("Error writing config to {}: {}", config_path.display(), e),
+    })?;
+
+    println!("Wrote config to {}", config_path.display());
+    Ok(())
+}
+
 fn open_note(note: &Note) {
     match &note.content {
         Some(note_content) => match note_content {
@@ -396,9 +845,21 @@ fn open_note(note: &Note) {
     };
 }
 
-fn archive_note(connection: sqlite::Connection, note_index: i64) -> sqlite::Result<()> {
-    let mut statement = connection
-        .prepare("SELECT id, title FROM notes WHERE archived = FALSE ORDER BY datetime")?;
+fn archive_note(
+    connection: sqlite::Connection,
+    note_index: i64,
+    category_filter: Option<&str>,
+) -> sqlite::Result<()> {
+    let mut statement = connection.prepare(format!(
+        "{}
+        SELECT notes.id, notes.title
+        FROM notes JOIN tree ON notes.id = tree.id
+        WHERE (? IS NULL OR notes.category = ?)
+        ORDER BY tree.sort_path",
+        TREE_CTE
+    ))?;
+    statement.bind(1, category_filter)?;
+    statement.bind(2, category_filter)?;
 
     let mut current_index = 0;
     while let State::Row = statement.next()? {
@@ -409,14 +870,20 @@ fn archive_note(connection: sqlite::Connection, note_index: i64) -> sqlite::Resu
 
         let id = statement.read::<i64>(0)?;
         let title = statement.read::<String>(1)?;
+
+        // Archiving a parent cascades to all of its descendants.
         let mut statement2 = connection.prepare(
-            " UPDATE notes
-            SET
-            archived = TRUE
-            WHERE id = ?
+            "
+            WITH RECURSIVE descendants(id) AS (
+                SELECT id FROM notes WHERE id = ?
+                UNION ALL
+                SELECT n.id FROM notes n JOIN descendants ON n.parent_id = descendants.id
+            )
+            UPDATE notes SET deleted_datetime = ? WHERE id IN (SELECT id FROM descendants)
             ",
         )?;
         statement2.bind(1, id)?;
+        statement2.bind(2, now_millis())?;
         statement2.next()?;
         println!("Note titled '{}' archived", title);
         return Ok(());
@@ -426,6 +893,110 @@ fn archive_note(connection: sqlite::Connection, note_index: i64) -> sqlite::Resu
     return Ok(());
 }
 
+fn restore_note(connection: sqlite::Connection, deleted_index: i64) -> sqlite::Result<()> {
+    let mut statement = connection.prepare(
+        "SELECT id, title FROM notes WHERE deleted_datetime IS NOT NULL ORDER BY deleted_datetime DESC",
+    )?;
+
+    let mut current_index = 0;
+    while let State::Row = statement.next()? {
+        if current_index != deleted_index {
+            current_index += 1;
+            continue;
+        }
+
+        let id = statement.read::<i64>(0)?;
+        let title = statement.read::<String>(1)?;
+        let mut statement2 =
+            connection.prepare("UPDATE notes SET deleted_datetime = NULL, archived = FALSE WHERE id = ?")?;
+        statement2.bind(1, id)?;
+        statement2.next()?;
+        println!("Note titled '{}' restored", title);
+        return Ok(());
+    }
+
+    println!("Note not found. Nothing restored");
+    return Ok(());
+}
+
+fn is_descendant(connection: &sqlite::Connection, ancestor_id: i64, note_id: i64) -> sqlite::Result<bool> {
+    let mut statement = connection.prepare(
+        "
+        WITH RECURSIVE descendants(id) AS (
+            SELECT id FROM notes WHERE id = ?
+            UNION ALL
+            SELECT n.id FROM notes n JOIN descendants ON n.parent_id = descendants.id
+        )
+        SELECT 1 FROM descendants WHERE id = ?
+        ",
+    )?;
+    statement.bind(1, ancestor_id)?;
+    statement.bind(2, note_id)?;
+    Ok(statement.next()? == State::Row)
+}
+
+fn id_at_index(
+    connection: &sqlite::Connection,
+    note_index: i64,
+    category_filter: Option<&str>,
+) -> sqlite::Result<Option<i64>> {
+    let mut statement = connection.prepare(format!(
+        "{}
+        SELECT notes.id
+        FROM notes JOIN tree ON notes.id = tree.id
+        WHERE (? IS NULL OR notes.category = ?)
+        ORDER BY tree.sort_path",
+        TREE_CTE
+    ))?;
+    statement.bind(1, category_filter)?;
+    statement.bind(2, category_filter)?;
+
+    let mut current_index = 0;
+    while let State::Row = statement.next()? {
+        if current_index != note_index {
+            current_index += 1;
+            continue;
+        }
+
+        return Ok(Some(statement.read::<i64>(0)?));
+    }
+
+    Ok(None)
+}
+
+fn move_note(
+    connection: sqlite::Connection,
+    from_index: i64,
+    to_index: i64,
+    category_filter: Option<&str>,
+) -> Result<(), RecallError> {
+    let from_id = id_at_index(&connection, from_index, category_filter)?;
+    let to_id = id_at_index(&connection, to_index, category_filter)?;
+
+    match (from_id, to_id) {
+        (Some(from_id), Some(to_id)) => {
+            if from_id == to_id || is_descendant(&connection, from_id, to_id)? {
+                return Err(RecallError {
+                    message: format!("Cannot move a note to become a descendant of itself"),
+                });
+            }
+
+            let position = next_position(&connection, Some(to_id))?;
+            let mut statement = connection.prepare(
+                "UPDATE notes SET parent_id = ?, position = ? WHERE id = ?",
+            )?;
+            statement.bind(1, Some(to_id))?;
+            statement.bind(2, position)?;
+            statement.bind(3, from_id)?;
+            statement.next()?;
+            Ok(())
+        }
+        _ => Err(RecallError {
+            message: format!("Note not found at the given index"),
+        }),
+    }
+}
+
 fn run(config: Config, options: Options) -> Result<(), RecallError> {
     let connection = sqlite::open(Path::new(&*shellexpand::tilde(&config.db_path)))?;
 
@@ -438,11 +1009,181 @@ fn run(config: Config, options: Options) -> Result<(), RecallError> {
             title TEXT NOT NULL,
             path TEXT,
             link TEXT,
-            text TEXT
+            text TEXT,
+            parent_id INTEGER,
+            position INTEGER NOT NULL DEFAULT 0,
+            updated_datetime INTEGER,
+            lastview_datetime INTEGER,
+            deleted_datetime INTEGER,
+            category TEXT
         );
         ",
     )?;
 
+    // Older databases were created before these columns existed.
+    for migration in &[
+        "ALTER TABLE notes ADD COLUMN parent_id INTEGER",
+        "ALTER TABLE notes ADD COLUMN position INTEGER NOT NULL DEFAULT 0",
+        "ALTER TABLE notes ADD COLUMN updated_datetime INTEGER",
+        "ALTER TABLE notes ADD COLUMN lastview_datetime INTEGER",
+        "ALTER TABLE notes ADD COLUMN deleted_datetime INTEGER",
+        "ALTER TABLE notes ADD COLUMN category TEXT",
+    ] {
+        match connection.execute(*migration) {
+            Ok(()) => (),
+            Err(e) if e.message.as_deref().unwrap_or("").contains("duplicate column name") => (),
+            Err(e) => return Err(RecallError::from(e)),
+        }
+    }
+
+    // Backfill deleted_datetime for rows archived under the old boolean-only scheme.
+    connection.execute(
+        format!(
+            "UPDATE notes SET deleted_datetime = {} WHERE archived = TRUE AND deleted_datetime IS NULL",
+            now_millis()
+        ),
+    )?;
+
+    connection.execute(
+        "
+        CREATE TABLE IF NOT EXISTS note_references (
+            source_id INTEGER NOT NULL,
+            target_title TEXT NOT NULL
+        );
+        ",
+    )?;
+
+    let fts_table_existed = connection
+        .prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'notes_fts'")?
+        .next()?
+        == State::Row;
+
+    connection.execute(
+        "
+        CREATE VIRTUAL TABLE IF NOT EXISTS notes_fts USING fts5(
+            title, text, link, content='notes', content_rowid='id'
+        );
+
+        CREATE TRIGGER IF NOT EXISTS notes_ai AFTER INSERT ON notes BEGIN
+            INSERT INTO notes_fts(rowid, title, text, link) VALUES (new.id, new.title, new.text, new.link);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS notes_ad AFTER DELETE ON notes BEGIN
+            INSERT INTO notes_fts(notes_fts, rowid, title, text, link) VALUES ('delete', old.id, old.title, old.text, old.link);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS notes_au AFTER UPDATE ON notes BEGIN
+            INSERT INTO notes_fts(notes_fts, rowid, title, text, link) VALUES ('delete', old.id, old.title, old.text, old.link);
+            INSERT INTO notes_fts(rowid, title, text, link) VALUES (new.id, new.title, new.text, new.link);
+        END;
+        ",
+    )?;
+
+    if !fts_table_existed {
+        // Backfill the FTS index for rows that existed before notes_fts was created.
+        connection.execute(
+            "INSERT INTO notes_fts(rowid, title, text, link) SELECT id, title, text, link FROM notes",
+        )?;
+    }
+
+    let category = options.category.clone();
+
+    if options.list && options.note_title_or_index.is_some() {
+        println!("--list cannot be combined with a note title or index");
+        return Ok(());
+    }
+
+    if let Some(query) = options.search {
+        for (note, snippet) in search_notes(connection, query.as_str())? {
+            println!("{}", note_display_string(&note));
+            println!("\t{}", snippet);
+        }
+        return Ok(());
+    }
+
+    if let Some(export) = options.export {
+        let target = export.parse::<Target>()?;
+        let notes: Vec<Note> = list_notes(connection, None)?.into_iter().map(|(note, _)| note).collect();
+        let document = compile(&notes, target);
+        match options.path {
+            Some(path) => {
+                fs::write(&path, document).map_err(|e| RecallError {
+                    message: format!("Error writing export to {}: {}", path, e),
+                })?;
+            }
+            None => println!("{}", document),
+        }
+        return Ok(());
+    }
+
+    if let Some(move_indices) = options.move_indices {
+        return match (move_indices.get(0), move_indices.get(1)) {
+            (Some(&from_index), Some(&to_index)) => {
+                move_note(connection, from_index, to_index, category.as_deref())
+            }
+            _ => {
+                println!("--move requires exactly two indices: <from> <to>");
+                Ok(())
+            }
+        };
+    }
+
+    if let Some(restore_index) = options.restore {
+        return Ok(restore_note(connection, restore_index)?);
+    }
+
+    if options.categories {
+        for (category, count) in list_categories(connection)? {
+            println!("{}\t{}", category.cyan(), count);
+        }
+        return Ok(());
+    }
+
+    if options.deleted {
+        for (i, note) in list_deleted_notes(connection)?.iter().enumerate() {
+            let deleted_display = match note.deleted_datetime_millis {
+                Some(deleted_millis) => chrono::DateTime::<chrono::Local>::from(
+                    std::time::UNIX_EPOCH
+                        + std::time::Duration::from_millis(u64::try_from(deleted_millis).unwrap()),
+                )
+                .format("%F %H:%M:%S")
+                .to_string(),
+                None => String::from("unknown"),
+            };
+            println!(
+                "{} {}\tdeleted {}",
+                format!("{}", i).bold(),
+                note.title.yellow(),
+                deleted_display
+            );
+        }
+        return Ok(());
+    }
+
+    if let Some(backlinks_index) = options.backlinks {
+        let note = read_nth_note(&connection, backlinks_index, category.as_deref())?;
+        for (title, datetime_millis) in find_backlinks(connection, note.title.as_str())? {
+            let time_display = chrono::DateTime::<chrono::Local>::from(
+                std::time::UNIX_EPOCH
+                    + std::time::Duration::from_millis(u64::try_from(datetime_millis).unwrap()),
+            )
+            .format("%F %H:%M:%S");
+            println!("{}\t{}", time_display, title.yellow());
+        }
+        return Ok(());
+    }
+
+    let parent_id = match options.parent {
+        Some(parent_index) => match id_at_index(&connection, parent_index, None)? {
+            Some(id) => Some(id),
+            None => {
+                println!("Parent note not found at index {}", parent_index);
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+
     if options.archive {
         return match options.note_title_or_index {
             None => {
@@ -452,7 +1193,7 @@ fn run(config: Config, options: Options) -> Result<(), RecallError> {
             Some(note_title_or_index) => {
                 let note_index = note_title_or_index.parse::<i64>();
                 match note_index {
-                    Ok(note_index) => Ok(archive_note(connection, note_index)?),
+                    Ok(note_index) => Ok(archive_note(connection, note_index, category.as_deref())?),
                     Err(err) => {
                         println!("Error parsing note index: {}", err);
                         Ok(())
@@ -467,15 +1208,20 @@ fn run(config: Config, options: Options) -> Result<(), RecallError> {
             let note_index = note_title_or_index.parse::<i64>();
             match note_index {
                 Ok(note_index) => {
-                    let note = read_nth_note(connection, note_index)?;
+                    let note = read_nth_note(&connection, note_index, category.as_deref())?;
+                    bump_lastview(&connection, note.id)?;
                     if options.edit {
+                        let note_id = note.id;
                         match note.content {
                             Some(note_content) => match note_content {
                                 NoteContent::Text(text) => {
                                     match edit_text_in_editor(&config, text) {
                                         Ok(new_text) => {
-                                            // TODO: save new text
-                                            Ok(())
+                                            Ok(update_note_text(
+                                                &connection,
+                                                note_id,
+                                                new_text.as_str(),
+                                            )?)
                                         }
                                         Err(err) => Err(RecallError::from(err)),
                                     }
@@ -491,6 +1237,15 @@ fn run(config: Config, options: Options) -> Result<(), RecallError> {
                     } else {
                         println!("{}", note_display_string(&note));
                         println!("{}", note_content_display_string(&note));
+                        if let Some(NoteContent::Text(text)) = &note.content {
+                            for target_title in find_references(text) {
+                                if reference_exists(&connection, target_title.as_str())? {
+                                    println!("  -> {}", target_title.yellow());
+                                } else {
+                                    println!("  -> {} {}", target_title.yellow(), "(unresolved)".italic());
+                                }
+                            }
+                        }
                         open_note(&note);
                         Ok(())
                     }
@@ -510,33 +1265,44 @@ fn run(config: Config, options: Options) -> Result<(), RecallError> {
                             let text = edit_text_in_editor(&config, String::from("")).unwrap();
                             Ok(insert_note(
                                 connection,
-                                Note::new_with_text(note_title, text),
+                                Note::new_with_text(note_title, text).with_parent(parent_id).with_category(category.clone()),
                             )?)
                         }
                     } else if let Some(path) = options.path {
                         Ok(insert_note(
                             connection,
-                            Note::new_with_path(note_title, path),
+                            Note::new_with_path(note_title, path).with_parent(parent_id).with_category(category.clone()),
                         )?)
                     } else if let Some(link) = options.link {
                         Ok(insert_note(
                             connection,
-                            Note::new_with_link(note_title, link),
+                            Note::new_with_link(note_title, link).with_parent(parent_id).with_category(category.clone()),
                         )?)
                     } else if let Some(text) = options.text {
                         Ok(insert_note(
                             connection,
-                            Note::new_with_text(note_title, text),
+                            Note::new_with_text(note_title, text).with_parent(parent_id).with_category(category.clone()),
                         )?)
                     } else {
-                        Ok(insert_note(connection, Note::new(note_title))?)
+                        Ok(insert_note(
+                            connection,
+                            Note::new(note_title).with_parent(parent_id).with_category(category.clone()),
+                        )?)
                     }
                 }
             }
         }
         None => {
-            for (i, note) in list_notes(connection)?.iter().enumerate() {
-                println!("{} {}", format!("{}", i).bold(), note_display_string(note));
+            let show_category = options.category.is_none();
+            for (i, (note, depth)) in list_notes(connection, options.category.as_deref())?
+                .iter()
+                .enumerate()
+            {
+                println!(
+                    "{} {}",
+                    format!("{}", i).bold(),
+                    note_display_string_at_depth(note, *depth, show_category)
+                );
             }
             Ok(())
         }
@@ -553,9 +1319,16 @@ fn main() {
                 Ok(config_string) => {
                     let maybe_config = toml::from_str::<Config>(&config_string);
                     match maybe_config {
-                        Ok(config) => match run(config, options) {
-                            Ok(()) => (),
-                            Err(err) => println!("{}", err)
+                        Ok(config) => {
+                            let result = if options.configure {
+                                configure(&config_file, config, &options)
+                            } else {
+                                run(config, options)
+                            };
+                            match result {
+                                Ok(()) => (),
+                                Err(err) => println!("{}", err)
+                            }
                         },
                         Err(err) => println!("Could not parse config located at {}: {}", config_file.display(), err)
                     }